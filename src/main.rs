@@ -1,28 +1,42 @@
 use regex::Regex;
 use serde_yaml::{Value};
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::{read_to_string, File};
+use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
 use log::{info, warn, error};
 use simple_logger::SimpleLogger;
 use std::process::Command;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
-fn run_command(cmd: &str, args: &[&str]) -> Result<(), Box<dyn Error>> {
-    let full_command = format!("{} {}", cmd, args.join(" "));
+// Directories/generated outputs pruned from the Dart walk by default, on top of .gitignore.
+// Extendable via exclude_globs in codegen_optimizer.yaml.
+const DEFAULT_EXCLUDE_GLOBS: &[&str] = &[
+    "**/.dart_tool/**",
+    "**/build/**",
+    "**/.pub-cache/**",
+    "**/*.g.dart",
+    "**/*.freezed.dart",
+];
+
+fn run_command(cmd: &Path, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let full_command = format!("{} {}", cmd.display(), args.join(" "));
     info!("Executing: {}", full_command);
-    
+
     let start = std::time::Instant::now();
     let output = Command::new(cmd)
         .args(args)
         .output()
         .map_err(|e| {
             if e.kind() == std::io::ErrorKind::NotFound {
-                format!("Command '{}' not found. Please ensure it is installed and in your PATH", cmd)
+                format!("Command '{}' not found. Please ensure it is installed and in your PATH", cmd.display())
             } else {
-                format!("Failed to execute command '{}': {}", cmd, e)
+                format!("Failed to execute command '{}': {}", cmd.display(), e)
             }
         })?;
 
@@ -33,7 +47,7 @@ fn run_command(cmd: &str, args: &[&str]) -> Result<(), Box<dyn Error>> {
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(format!(
             "Command '{} {}' failed with status {}: {}",
-            cmd,
+            cmd.display(),
             args.join(" "),
             output.status,
             stderr
@@ -42,93 +56,350 @@ fn run_command(cmd: &str, args: &[&str]) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn check_flutter_installed() -> Result<(), Box<dyn Error>> {
-    // Get the PATH environment variable
-    let path_var = std::env::var("PATH").unwrap_or_else(|_| "PATH not set".to_string());
-    info!("Current PATH: {}", path_var);
-
-    // Try to find flutter in PATH
-    let flutter_path = which::which("flutter")
-        .map_err(|e| format!("Failed to find flutter in PATH: {}\nPATH: {}", e, path_var))?;
-    info!("Found flutter at: {}", flutter_path.display());
-
-    // On Windows, we need to use flutter.bat
-    let flutter_cmd = if cfg!(windows) {
-        "flutter.bat"
-    } else {
-        "flutter"
-    };
-    
-    // Try running flutter --version
-    run_command(flutter_cmd, &["--version"])?;
-    Ok(())
+// Flutter release channel and version reported by `flutter --version`.
+#[derive(Debug, Clone)]
+struct FlutterVersion {
+    channel: String,
+    version: String,
 }
 
-#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
-enum AnnotationType {
-    CopyWith,
-    JsonSerializable,
-    Hive,
+impl FlutterVersion {
+    // Parses "Flutter 3.16.0 • channel stable • https://github.com/flutter/flutter.git".
+    fn parse(raw: &str) -> Result<Self, Box<dyn Error>> {
+        let first_line = raw.lines().next().ok_or("empty `flutter --version` output")?;
+        let mut segments = first_line.split('\u{2022}');
+
+        let version = segments
+            .next()
+            .and_then(|s| s.trim().strip_prefix("Flutter "))
+            .map(|s| s.trim().to_string())
+            .ok_or_else(|| format!("unexpected `flutter --version` output: {:?}", first_line))?;
+
+        let channel = segments
+            .next()
+            .and_then(|s| s.trim().strip_prefix("channel "))
+            .map(|s| s.trim().to_string())
+            .ok_or_else(|| format!("unexpected `flutter --version` output: {:?}", first_line))?;
+
+        Ok(Self { channel, version })
+    }
+
+    // Compares dotted version numbers component-wise, treating a missing component as 0.
+    fn at_least(&self, min: &str) -> bool {
+        fn components(s: &str) -> Vec<u64> {
+            s.split('.')
+                .map(|part| part.chars().take_while(|c| c.is_ascii_digit()).collect::<String>())
+                .map(|digits| digits.parse().unwrap_or(0))
+                .collect()
+        }
+        let (mut have, mut want) = (components(&self.version), components(min));
+        let len = have.len().max(want.len());
+        have.resize(len, 0);
+        want.resize(len, 0);
+        have >= want
+    }
 }
 
-#[derive(Debug, Clone)]
-struct AnnotationPattern {
-    pattern: &'static str,
-    builder_key: &'static str,
+// Minimum Flutter version this tool will run build_runner against.
+const MIN_FLUTTER_VERSION: &str = "3.0.0";
+
+// The resolved Flutter SDK this run will use: its root directory and reported version.
+struct FlutterSdk {
+    root: PathBuf,
+    version: FlutterVersion,
 }
 
-impl AnnotationPattern {
-    fn compile(&self) -> Regex {
-        Regex::new(self.pattern).unwrap()
+impl FlutterSdk {
+    fn executable_name() -> &'static str {
+        if cfg!(windows) { "flutter.bat" } else { "flutter" }
+    }
+
+    fn executable_path(root: &Path) -> PathBuf {
+        root.join("bin").join(Self::executable_name())
+    }
+
+    // Honors FLUTTER_ROOT when set; otherwise resolves flutter via PATH and derives the SDK
+    // root from its canonicalized path (<root>/bin/flutter).
+    fn resolve_root() -> Result<PathBuf, Box<dyn Error>> {
+        if let Ok(flutter_root) = std::env::var("FLUTTER_ROOT") {
+            let root = PathBuf::from(&flutter_root);
+            return root
+                .canonicalize()
+                .map_err(|e| format!("FLUTTER_ROOT '{}' is not a valid path: {}", flutter_root, e).into());
+        }
+
+        let path_var = std::env::var("PATH").unwrap_or_else(|_| "PATH not set".to_string());
+        let flutter_path = which::which("flutter")
+            .map_err(|e| format!("Failed to find flutter in PATH: {}\nPATH: {}", e, path_var))?;
+        let canonical = flutter_path
+            .canonicalize()
+            .map_err(|e| format!("Failed to canonicalize {:?}: {}", flutter_path, e))?;
+
+        canonical
+            .parent()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .ok_or_else(|| format!("Could not derive FLUTTER_ROOT from flutter executable at {:?}", canonical).into())
+    }
+
+    // Resolves the SDK root and refuses to continue if the version is below min_version.
+    fn resolve(min_version: &str) -> Result<Self, Box<dyn Error>> {
+        let root = Self::resolve_root()?;
+        info!("Using Flutter SDK at: {}", root.display());
+
+        let flutter_bin = Self::executable_path(&root);
+        let output = Command::new(&flutter_bin)
+            .arg("--version")
+            .output()
+            .map_err(|e| format!("Failed to execute '{} --version': {}", flutter_bin.display(), e))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("'{} --version' failed: {}", flutter_bin.display(), stderr).into());
+        }
+
+        let version = FlutterVersion::parse(&String::from_utf8_lossy(&output.stdout))?;
+        if !version.at_least(min_version) {
+            return Err(format!(
+                "Flutter {} ({} channel) at {} is older than the minimum supported version {}. \
+                 Please upgrade with `flutter upgrade` before running codegen_optimizer.",
+                version.version, version.channel, root.display(), min_version
+            ).into());
+        }
+
+        info!("Flutter {} ({} channel)", version.version, version.channel);
+        Ok(Self { root, version })
+    }
+
+    fn command_path(&self) -> PathBuf {
+        Self::executable_path(&self.root)
+    }
+}
+
+// An annotation pattern mapped to the builder's generate_for it belongs in, e.g. `@CopyWith(`
+// -> `copy_with_extension_gen`.
+#[derive(Debug, Clone, serde::Deserialize)]
+struct AnnotationRule {
+    name: String,
+    pattern: String,
+    builder_key: String,
+}
+
+// On-disk shape of codegen_optimizer.yaml.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+struct OptimizerConfig {
+    #[serde(default)]
+    rules: Vec<AnnotationRule>,
+    #[serde(default)]
+    exclude_globs: Vec<String>,
+    #[serde(default)]
+    min_flutter_version: Option<String>,
+}
+
+impl OptimizerConfig {
+    const FILE_NAME: &'static str = "codegen_optimizer.yaml";
+
+    // Returns the default (empty) config when no codegen_optimizer.yaml exists.
+    fn load(working_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let config_path = working_dir.join(Self::FILE_NAME);
+        if !config_path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(&config_path)?;
+        let config: OptimizerConfig = serde_yaml::from_str(&content)
+            .map_err(|e| format!("Failed to parse {}: {}", Self::FILE_NAME, e))?;
+        Ok(config)
     }
 }
 
-struct PatternRegistry;
+struct PatternRegistry {
+    rules: Vec<AnnotationRule>,
+}
 
 impl PatternRegistry {
-    fn get_patterns() -> HashMap<AnnotationType, AnnotationPattern> {
-        let mut map = HashMap::new();
-        map.insert(
-            AnnotationType::CopyWith,
-            AnnotationPattern {
-                pattern: r"@CopyWith\s*\(",
-                builder_key: "copy_with_extension_gen",
+    fn default_rules() -> Vec<AnnotationRule> {
+        vec![
+            AnnotationRule {
+                name: "copy_with".to_string(),
+                pattern: r"@CopyWith\s*\(".to_string(),
+                builder_key: "copy_with_extension_gen".to_string(),
             },
-        );
-        map.insert(
-            AnnotationType::JsonSerializable,
-            AnnotationPattern {
-                pattern: r"@JsonSerializable\s*\(",
-                builder_key: "json_serializable",
+            AnnotationRule {
+                name: "json_serializable".to_string(),
+                pattern: r"@JsonSerializable\s*\(".to_string(),
+                builder_key: "json_serializable".to_string(),
             },
-        );
-        map.insert(
-            AnnotationType::Hive,
-            AnnotationPattern {
-                pattern: r"@HiveType\s*\(",
-                builder_key: "hive_generator",
+            AnnotationRule {
+                name: "hive".to_string(),
+                pattern: r"@HiveType\s*\(".to_string(),
+                builder_key: "hive_generator".to_string(),
             },
-        );
-        map
+        ]
+    }
+
+    // Merges configured rules into the built-in copy_with/json_serializable/hive defaults,
+    // overriding a default by name instead of discarding the others.
+    fn from_rules(user_rules: Vec<AnnotationRule>) -> Result<Self, Box<dyn Error>> {
+        let mut rules = Self::default_rules();
+        for user_rule in user_rules {
+            if let Some(existing) = rules.iter_mut().find(|r| r.name == user_rule.name) {
+                *existing = user_rule;
+            } else {
+                rules.push(user_rule);
+            }
+        }
+
+        for rule in &rules {
+            Regex::new(&rule.pattern).map_err(|e| {
+                format!(
+                    "Invalid regex pattern for annotation rule '{}' ({:?}): {}",
+                    rule.name, rule.pattern, e
+                )
+            })?;
+        }
+
+        Ok(Self { rules })
+    }
+
+    fn compiled(&self) -> Result<Vec<(AnnotationRule, Regex)>, Box<dyn Error>> {
+        self.rules
+            .iter()
+            .map(|rule| {
+                Regex::new(&rule.pattern)
+                    .map(|regex| (rule.clone(), regex))
+                    .map_err(|e| -> Box<dyn Error> {
+                        format!("Invalid regex pattern for annotation rule '{}': {}", rule.name, e).into()
+                    })
+            })
+            .collect()
+    }
+}
+
+// Builds the walk overrides from DEFAULT_EXCLUDE_GLOBS plus the project's exclude_globs, all
+// treated as exclude patterns regardless of a leading `!`.
+fn build_walk_excludes(working_dir: &Path, extra_globs: &[String]) -> Result<Override, Box<dyn Error>> {
+    let mut builder = OverrideBuilder::new(working_dir);
+    for glob in DEFAULT_EXCLUDE_GLOBS.iter().map(|g| g.to_string()).chain(extra_globs.iter().cloned()) {
+        let exclude_glob = glob.strip_prefix('!').unwrap_or(&glob);
+        builder.add(&format!("!{}", exclude_glob))?;
+    }
+    Ok(builder.build()?)
+}
+
+// Generate rewrites build.yaml; Check (--check) only verifies it's current, for CI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Generate,
+    Check,
+}
+
+impl Mode {
+    fn from_args<S: AsRef<str>>(args: &[S]) -> Self {
+        if args.iter().any(|a| a.as_ref() == "--check") {
+            Mode::Check
+        } else {
+            Mode::Generate
+        }
+    }
+}
+
+// Whether --force was passed, bypassing BuildCache and always running build_runner.
+fn has_force_flag<S: AsRef<str>>(args: &[S]) -> bool {
+    args.iter().any(|a| a.as_ref() == "--force")
+}
+
+// Result of a single-pass annotation scan: per-rule matches plus a content hash per matched
+// source, used by BuildCache to detect whether anything relevant changed since the last run.
+struct AnnotationScan {
+    matches_by_rule: HashMap<String, Vec<String>>,
+    source_hashes: HashMap<String, String>,
+}
+
+// Returns the mapping stored under `key`, creating it (and overwriting a non-mapping value)
+// if it isn't already a mapping, so callers can write into a nested YAML path that build.yaml
+// may not have populated yet (e.g. a builder_key with no existing entry).
+fn mapping_entry<'a>(map: &'a mut serde_yaml::Mapping, key: &str) -> &'a mut serde_yaml::Mapping {
+    let key = Value::String(key.to_string());
+    if !matches!(map.get(&key), Some(Value::Mapping(_))) {
+        map.insert(key.clone(), Value::Mapping(serde_yaml::Mapping::new()));
+    }
+    map.get_mut(&key).unwrap().as_mapping_mut().unwrap()
+}
+
+fn hash_contents(content: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+// Cache of the last successful build_runner run's inputs, compared against a fresh scan so
+// `main` can skip `flutter clean`/`pub get`/`build_runner build` when nothing changed.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+struct BuildCache {
+    #[serde(default)]
+    sources: HashMap<String, String>,
+    #[serde(default)]
+    generate_for: HashMap<String, Vec<String>>,
+}
+
+impl BuildCache {
+    const FILE_NAME: &'static str = ".codegen_optimizer_cache.json";
+
+    fn path(working_dir: &Path) -> PathBuf {
+        working_dir.join(Self::FILE_NAME)
+    }
+
+    // Missing or unparsable cache files are treated as "nothing cached yet".
+    fn load(working_dir: &Path) -> Self {
+        read_to_string(Self::path(working_dir))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
     }
 
-    fn get_pattern(annotation_type: &AnnotationType) -> Option<AnnotationPattern> {
-        Self::get_patterns().get(annotation_type).cloned()
+    fn save(
+        working_dir: &Path,
+        sources: HashMap<String, String>,
+        generate_for: HashMap<String, Vec<String>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let cache = Self { sources, generate_for };
+        let content = serde_json::to_string_pretty(&cache)?;
+        let mut file = File::create(Self::path(working_dir))?;
+        file.write_all(content.as_bytes())?;
+        Ok(())
     }
 }
 
+// Result of a generate run, compared against BuildCache to decide if build_runner needs to run.
+struct GenerationOutcome {
+    source_hashes: HashMap<String, String>,
+    generate_for: HashMap<String, Vec<String>>,
+}
+
 struct BuildYamlGenerator {
     working_dir: PathBuf,
     build_yaml_path: PathBuf,
+    mode: Mode,
+    pattern_registry: PatternRegistry,
+    walk_excludes: Override,
+    min_flutter_version: String,
 }
 
 impl BuildYamlGenerator {
-    fn new(working_dir: PathBuf) -> Self {
+    fn new(working_dir: PathBuf, mode: Mode) -> Result<Self, Box<dyn Error>> {
         let build_yaml_path = working_dir.join("build.yaml");
-        Self {
+        let config = OptimizerConfig::load(&working_dir)?;
+        let pattern_registry = PatternRegistry::from_rules(config.rules)?;
+        let walk_excludes = build_walk_excludes(&working_dir, &config.exclude_globs)?;
+        let min_flutter_version = config.min_flutter_version.unwrap_or_else(|| MIN_FLUTTER_VERSION.to_string());
+        Ok(Self {
             working_dir,
             build_yaml_path,
-        }
+            mode,
+            pattern_registry,
+            walk_excludes,
+            min_flutter_version,
+        })
     }
 
     fn read_yaml_file(&self) -> Result<Value, Box<dyn Error>> {
@@ -137,37 +408,74 @@ impl BuildYamlGenerator {
         Ok(yaml)
     }
 
-    fn find_files_with_annotation(&self, annotation_type: &AnnotationType) -> Result<Vec<String>, Box<dyn Error>> {
-        let pattern_info = PatternRegistry::get_pattern(annotation_type)
-            .ok_or_else(|| format!("Unsupported annotation type: {:?}", annotation_type))?;
-        let regex = pattern_info.compile();
+    // Walks working_dir once, reading each .dart file a single time and testing every compiled
+    // rule against it in parallel, instead of re-walking once per rule.
+    fn scan(&self) -> Result<AnnotationScan, Box<dyn Error>> {
+        let compiled = self.pattern_registry.compiled()?;
+
+        let entries: Vec<PathBuf> = WalkBuilder::new(&self.working_dir)
+            .standard_filters(true)
+            .overrides(self.walk_excludes.clone())
+            .build()
+            .filter_map(|e| e.ok())
+            .map(|e| e.into_path())
+            .filter(|p| p.extension().and_then(|s| s.to_str()) == Some("dart"))
+            .collect();
 
-        let mut files_with_annotation = Vec::new();
+        type Accumulator = (HashMap<String, Vec<String>>, HashMap<String, String>);
 
-        for entry in WalkDir::new(&self.working_dir).into_iter().filter_map(|e| e.ok()) {
-            if entry.path().extension().and_then(|s| s.to_str()) == Some("dart") {
-                match std::fs::read_to_string(entry.path()) {
-                    Ok(content) => {
-                        if regex.is_match(&content) {
-                            let processed = self.process_part_of(entry.path(), &content);
-                            files_with_annotation.push(processed.display().to_string());
+        let (matches_by_rule, source_hashes): Accumulator = entries
+            .into_par_iter()
+            .fold(
+                || (HashMap::new(), HashMap::new()),
+                |(mut matches, mut hashes): Accumulator, path| {
+                    match read_to_string(&path) {
+                        Ok(content) => {
+                            let mut matched_any = false;
+                            for (rule, regex) in &compiled {
+                                if regex.is_match(&content) {
+                                    matched_any = true;
+                                    let processed = self.process_part_of(&path, &content);
+                                    matches
+                                        .entry(rule.name.clone())
+                                        .or_default()
+                                        .push(processed.display().to_string());
+                                }
+                            }
+                            if matched_any {
+                                hashes.insert(path.display().to_string(), hash_contents(&content));
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Error processing file {:?}: {}", path, e);
                         }
                     }
-                    Err(e) => {
-                        warn!("Error processing file {:?}: {}", entry.path(), e);
-                        continue;
+                    (matches, hashes)
+                },
+            )
+            .reduce(
+                || (HashMap::new(), HashMap::new()),
+                |(mut matches_a, mut hashes_a), (matches_b, hashes_b)| {
+                    for (rule_name, files) in matches_b {
+                        matches_a.entry(rule_name).or_default().extend(files);
                     }
-                }
-            }
+                    hashes_a.extend(hashes_b);
+                    (matches_a, hashes_a)
+                },
+            );
+
+        // Wrap with quotes as in Python code, and sort for deterministic output.
+        let mut matches_by_rule_quoted = HashMap::new();
+        for (rule_name, files) in matches_by_rule {
+            let mut quoted_files: Vec<String> = files.iter().map(|f| format!("\"{}\"", f)).collect();
+            quoted_files.sort();
+            matches_by_rule_quoted.insert(rule_name, quoted_files);
         }
 
-        // Wrap with quotes as in Python code.
-        let mut quoted_files: Vec<String> = files_with_annotation
-            .iter()
-            .map(|f| format!("\"{}\"", f))
-            .collect();
-        quoted_files.sort();
-        Ok(quoted_files)
+        Ok(AnnotationScan {
+            matches_by_rule: matches_by_rule_quoted,
+            source_hashes,
+        })
     }
 
     fn process_part_of(&self, file_path: &Path, content: &str) -> PathBuf {
@@ -197,28 +505,43 @@ impl BuildYamlGenerator {
         Ok(())
     }
 
-    fn update_build_yaml(&self) -> Result<(), Box<dyn Error>> {
+    // Maps a scan's per-rule matches onto each builder's generate_for key.
+    fn compute_generate_for(&self, scan: &AnnotationScan) -> HashMap<String, Vec<String>> {
+        let mut intended: HashMap<String, Vec<String>> = HashMap::new();
+        for rule in &self.pattern_registry.rules {
+            let files = scan.matches_by_rule.get(&rule.name).cloned().unwrap_or_default();
+            intended.entry(rule.builder_key.clone()).or_default().extend(files);
+        }
+        // Multiple rules can target the same builder_key; keep the merged list sorted and unique.
+        for files in intended.values_mut() {
+            files.sort();
+            files.dedup();
+        }
+        intended
+    }
+
+    // Rewrites build.yaml's generate_for lists from a fresh annotation scan.
+    fn update_build_yaml(&self) -> Result<GenerationOutcome, Box<dyn Error>> {
         info!("Generating build.yaml for {:?}", &self.working_dir);
         let mut yaml_content = self.read_yaml_file()?;
 
-        let patterns = PatternRegistry::get_patterns();
-        for (annotation_type, pattern_info) in patterns.iter() {
-            if let Ok(files) = self.find_files_with_annotation(annotation_type) {
-                // Navigate the YAML structure to update the generate_for field
-                // Assuming the YAML structure matches the Python code.
-                if let Some(targets) = yaml_content.get_mut("targets") {
-                    if let Some(default) = targets.get_mut("$default") {
-                        if let Some(builders) = default.get_mut("builders") {
-                            if let Some(builder) = builders.get_mut(pattern_info.builder_key) {
-                                if let Some(generate_for) = builder.get_mut("generate_for") {
-                                    // Replace with new list of files.
-                                    *generate_for = Value::Sequence(files.into_iter().map(Value::String).collect());
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        let scan = self.scan()?;
+        let intended = self.compute_generate_for(&scan);
+
+        if !yaml_content.is_mapping() {
+            yaml_content = Value::Mapping(serde_yaml::Mapping::new());
+        }
+        let root = yaml_content.as_mapping_mut().unwrap();
+        let targets = mapping_entry(root, "targets");
+        let default = mapping_entry(targets, "$default");
+        let builders = mapping_entry(default, "builders");
+
+        for (builder_key, files) in &intended {
+            let builder = mapping_entry(builders, builder_key.as_str());
+            builder.insert(
+                Value::String("generate_for".to_string()),
+                Value::Sequence(files.iter().cloned().map(Value::String).collect()),
+            );
         }
 
         // Write YAML back
@@ -229,36 +552,112 @@ impl BuildYamlGenerator {
 
         self.format_build_yaml()?;
         info!("Successfully updated build.yaml");
-        Ok(())
+        Ok(GenerationOutcome {
+            source_hashes: scan.source_hashes,
+            generate_for: intended,
+        })
+    }
+
+    // Compares the intended generate_for lists against the on-disk build.yaml without writing
+    // anything, logging which builder keys differ.
+    fn check_build_yaml(&self) -> Result<bool, Box<dyn Error>> {
+        info!("Checking build.yaml for {:?}", &self.working_dir);
+        let yaml_content = self.read_yaml_file()?;
+        let scan = self.scan()?;
+        let intended = self.compute_generate_for(&scan);
+
+        // Bring both sides to the form format_build_yaml leaves on disk: no surrounding quotes,
+        // paths relative to working_dir with `/` separators.
+        let prefix = format!("{}{}", self.working_dir.display(), std::path::MAIN_SEPARATOR);
+        let normalize = |f: &String| -> String {
+            f.replace('\'', "").replace(&prefix, "").replace(std::path::MAIN_SEPARATOR, "/").trim_matches('"').to_string()
+        };
+
+        let mut is_current = true;
+        for (builder_key, intended_files) in &intended {
+            let current_files: Vec<String> = yaml_content
+                .get("targets")
+                .and_then(|t| t.get("$default"))
+                .and_then(|d| d.get("builders"))
+                .and_then(|b| b.get(builder_key.as_str()))
+                .and_then(|b| b.get("generate_for"))
+                .and_then(|g| g.as_sequence())
+                .map(|seq| seq.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+                .unwrap_or_default();
+
+            let current_set: HashSet<String> = current_files.iter().map(normalize).collect();
+            let intended_set: HashSet<String> = intended_files.iter().map(normalize).collect();
+
+            if current_set != intended_set {
+                is_current = false;
+                warn!("Builder '{}' is stale in build.yaml:", builder_key);
+                for added in intended_set.difference(&current_set) {
+                    info!("  + {}", added);
+                }
+                for removed in current_set.difference(&intended_set) {
+                    info!("  - {}", removed);
+                }
+            }
+        }
+
+        if is_current {
+            info!("build.yaml is up to date");
+        } else {
+            error!("build.yaml is stale; re-run codegen_optimizer (without --check) to regenerate it");
+        }
+        Ok(is_current)
     }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     SimpleLogger::new().init().unwrap();
 
-    // Check if flutter is installed before proceeding
-    if let Err(e) = check_flutter_installed() {
-        error!("{}", e);
-        error!("Please install Flutter and ensure it's in your PATH before running this tool.");
-        error!("You can download Flutter from: https://flutter.dev");
-        return Err(e);
-    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = Mode::from_args(&args);
+    let force = has_force_flag(&args);
 
     let current_dir = std::env::current_dir()?;
-    let generator = BuildYamlGenerator::new(current_dir);
+    let generator = BuildYamlGenerator::new(current_dir, mode)?;
+
+    if generator.mode == Mode::Check {
+        // `--check` only verifies build.yaml against the annotation scan; it must not touch
+        // Flutter or the toolchain at all.
+        return if generator.check_build_yaml()? {
+            Ok(())
+        } else {
+            std::process::exit(1);
+        };
+    }
+
+    // Resolve and version-gate the Flutter SDK before proceeding.
+    let sdk = match FlutterSdk::resolve(&generator.min_flutter_version) {
+        Ok(sdk) => sdk,
+        Err(e) => {
+            error!("{}", e);
+            error!("Please install Flutter and ensure it's in your PATH (or set FLUTTER_ROOT) before running this tool.");
+            error!("You can download Flutter from: https://flutter.dev");
+            return Err(e);
+        }
+    };
+
     match generator.update_build_yaml() {
-        Ok(_) => {
-            // Run Flutter commands sequentially after update_build_yaml
-            let flutter_cmd = if cfg!(windows) {
-                "flutter.bat"
-            } else {
-                "flutter"
-            };
-            
-            run_command(flutter_cmd, &["clean"])?;
-            run_command(flutter_cmd, &["pub", "upgrade"])?;
-            run_command(flutter_cmd, &["pub", "get"])?;
-            run_command(flutter_cmd, &["pub", "run", "build_runner", "build", "--delete-conflicting-outputs"])?;
+        Ok(GenerationOutcome { source_hashes, generate_for }) => {
+            let previous_cache = BuildCache::load(&generator.working_dir);
+            if !force && previous_cache.sources == source_hashes && previous_cache.generate_for == generate_for {
+                info!("no codegen inputs changed, skipping build_runner");
+                return Ok(());
+            }
+
+            // Run Flutter commands sequentially against the resolved SDK after update_build_yaml
+            info!("Running build_runner with Flutter {} ({} channel)", sdk.version.version, sdk.version.channel);
+            let flutter_cmd = sdk.command_path();
+
+            run_command(&flutter_cmd, &["clean"])?;
+            run_command(&flutter_cmd, &["pub", "upgrade"])?;
+            run_command(&flutter_cmd, &["pub", "get"])?;
+            run_command(&flutter_cmd, &["pub", "run", "build_runner", "build", "--delete-conflicting-outputs"])?;
+
+            BuildCache::save(&generator.working_dir, source_hashes, generate_for)?;
             Ok(())
         },
         Err(e) => {
@@ -267,3 +666,87 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flutter_version_parses_standard_output() {
+        let raw = "Flutter 3.16.0 \u{2022} channel stable \u{2022} https://github.com/flutter/flutter.git\n\
+                    Framework \u{2022} revision abcdef1234";
+        let version = FlutterVersion::parse(raw).unwrap();
+        assert_eq!(version.version, "3.16.0");
+        assert_eq!(version.channel, "stable");
+    }
+
+    #[test]
+    fn flutter_version_at_least_compares_component_wise() {
+        let version = FlutterVersion { channel: "stable".to_string(), version: "3.16.0".to_string() };
+        assert!(version.at_least("3.0.0"));
+        assert!(version.at_least("3.16.0"));
+        assert!(!version.at_least("3.17.0"));
+        assert!(!version.at_least("4.0.0"));
+    }
+
+    #[test]
+    fn pattern_registry_merges_user_rules_into_defaults_by_name() {
+        let registry = PatternRegistry::from_rules(vec![
+            AnnotationRule {
+                name: "json_serializable".to_string(),
+                pattern: r"@JsonSerializable\(explicitToJson".to_string(),
+                builder_key: "json_serializable".to_string(),
+            },
+            AnnotationRule {
+                name: "freezed".to_string(),
+                pattern: r"@freezed".to_string(),
+                builder_key: "freezed_builder".to_string(),
+            },
+        ])
+        .unwrap();
+
+        let names: Vec<&str> = registry.rules.iter().map(|r| r.name.as_str()).collect();
+        assert!(names.contains(&"copy_with"));
+        assert!(names.contains(&"hive"));
+        assert!(names.contains(&"freezed"));
+
+        let json_rule = registry.rules.iter().find(|r| r.name == "json_serializable").unwrap();
+        assert_eq!(json_rule.pattern, r"@JsonSerializable\(explicitToJson");
+    }
+
+    #[test]
+    fn pattern_registry_rejects_invalid_regex() {
+        let result = PatternRegistry::from_rules(vec![AnnotationRule {
+            name: "broken".to_string(),
+            pattern: "(".to_string(),
+            builder_key: "broken_builder".to_string(),
+        }]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn build_cache_round_trips_through_save_and_load() {
+        let working_dir = std::env::temp_dir().join(format!("codegen_optimizer_test_{}", std::process::id()));
+        std::fs::create_dir_all(&working_dir).unwrap();
+
+        let mut sources = HashMap::new();
+        sources.insert("lib/foo.dart".to_string(), "abc123".to_string());
+        let mut generate_for = HashMap::new();
+        generate_for.insert("json_serializable".to_string(), vec!["\"lib/foo.dart\"".to_string()]);
+
+        BuildCache::save(&working_dir, sources.clone(), generate_for.clone()).unwrap();
+        let loaded = BuildCache::load(&working_dir);
+
+        assert_eq!(loaded.sources, sources);
+        assert_eq!(loaded.generate_for, generate_for);
+
+        std::fs::remove_dir_all(&working_dir).unwrap();
+    }
+
+    #[test]
+    fn build_cache_load_defaults_when_missing() {
+        let working_dir = std::env::temp_dir().join(format!("codegen_optimizer_test_missing_{}", std::process::id()));
+        let loaded = BuildCache::load(&working_dir);
+        assert_eq!(loaded, BuildCache::default());
+    }
+}